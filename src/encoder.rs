@@ -1,21 +1,37 @@
 use super::csprng::*;
+use super::gadget::Gadget;
 use super::ring::*;
+use super::sampler::SamplerKind;
+use super::simd;
 use super::*;
 use primitive_types::U256;
-use rug::Float;
-use rug::{ops::*, Assign, Integer};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
 pub struct Encoder<'a> {
     pub params: &'a Parameters,
-    pub sampler: KarneySampler,
+    pub sampler: SamplerKind,
+    gadget: Gadget,
 }
 
 impl<'a> Encoder<'a> {
-    /// Creates a new encoder.
+    /// Creates a new encoder, sampling gaussian noise via the (data-dependent
+    /// timing) Karney rejection sampler.
     pub fn new(params: &'a Parameters) -> Encoder<'a> {
         Encoder {
             params: params,
-            sampler: KarneySampler::new(),
+            sampler: SamplerKind::Karney(KarneySampler::new()),
+            gadget: Gadget::new(params.b, params.kap, params.p),
+        }
+    }
+
+    /// Creates a new encoder with an explicit sampler choice, e.g. a
+    /// `SamplerKind::Cdt` built for the `sigma` the encoder will be used with.
+    pub fn new_with_sampler(params: &'a Parameters, sampler: SamplerKind) -> Encoder<'a> {
+        Encoder {
+            params: params,
+            sampler,
+            gadget: Gadget::new(params.b, params.kap, params.p),
         }
     }
 
@@ -33,12 +49,9 @@ impl<'a> Encoder<'a> {
         let params = &self.params;
         pout.clear();
         for (i, a) in v.iter().enumerate() {
-            let mut amod = a % self.params.p;
-            for j in 0..self.params.kap - 1 {
-                pout.coeffs[0][i + j * params.m] = (amod % params.b).as_u64();
-                amod /= params.b;
+            for (j, d) in self.gadget.decompose(*a).into_iter().enumerate() {
+                pout.coeffs[0][i + j * params.m] = d;
             }
-            pout.coeffs[0][i + params.m * (params.kap - 1)] = amod.as_u64();
         }
 
         pout.is_ntt = false;
@@ -46,6 +59,7 @@ impl<'a> Encoder<'a> {
     }
 
     /// Encodes a chunk of vectors of U256 into a chunk of polynomials.
+    #[cfg(not(feature = "rayon"))]
     pub fn encode_chunk_assign(&mut self, v: &[U256], pout: &mut [Poly]) {
         if v.len() != pout.len() * self.params.m {
             panic!("invalid length");
@@ -55,17 +69,18 @@ impl<'a> Encoder<'a> {
         }
     }
 
-    /// Computes pout += p * cx^d.
-    /// d must be smaller than p.len().
-    #[inline]
-    fn monomial_mul_and_add_assign(&self, p: &[f64], c: f64, d: usize, pout: &mut [f64]) {
-        let n = p.len();
-        for i in 0..n - d {
-            pout[i + d] += c * p[i];
-        }
-        for i in n - d..n {
-            pout[i + d - n] -= c * p[i];
+    /// Encodes a chunk of vectors of U256 into a chunk of polynomials.
+    /// `encode_assign` takes `&self`, so the chunk is split across tasks
+    /// directly without any per-task state to set up.
+    #[cfg(feature = "rayon")]
+    pub fn encode_chunk_assign(&mut self, v: &[U256], pout: &mut [Poly]) {
+        if v.len() != pout.len() * self.params.m {
+            panic!("invalid length");
         }
+        let m = self.params.m;
+        pout.par_iter_mut().enumerate().for_each(|(i, p)| {
+            self.encode_assign(&v[i * m..(i + 1) * m], p);
+        });
     }
 
     /// Encodes a vector of U256 into a polynomial, with gaussian noise.
@@ -80,41 +95,50 @@ impl<'a> Encoder<'a> {
     /// v must be of length lesser than m.
     pub fn encode_randomized_assign(&mut self, v: &[U256], sigma: f64, pout: &mut Poly) {
         let params = self.params;
+        let gadget = &self.gadget;
+        let sampler = &mut self.sampler;
+        Self::encode_randomized_core(params, gadget, v, pout, |c| sampler.sample_coset(c, sigma));
+    }
+
+    /// Shared body of `encode_randomized_assign`, parameterized over how a
+    /// coset sample is drawn so callers can plug in a per-call
+    /// `&mut SamplerKind`, or (for `encode_randomized_chunk_assign`'s rayon
+    /// path) a per-task `KarneySampler` or a `&CdtSampler` shared read-only
+    /// across tasks.
+    fn encode_randomized_core(
+        params: &Parameters,
+        gadget: &Gadget,
+        v: &[U256],
+        pout: &mut Poly,
+        mut sample: impl FnMut(f64) -> f64,
+    ) {
         pout.clear();
 
-        let mut buff0 = vec![0.0; params.n];
-        let mut buff1 = vec![0.0; params.n];
+        let mut buff0 = simd::aligned_f64_vec(params.n);
+        let mut buff1 = simd::aligned_f64_vec(params.n);
 
         // Encode v to float
-        let bf64 = self.params.b as f64;
+        let bf64 = params.b as f64;
         for (i, a) in v.iter().enumerate() {
-            let mut amod = a % self.params.p;
-            for j in 0..self.params.kap - 1 {
-                buff0[i + j * params.m] = (amod % params.b).as_u64() as f64;
-                amod /= params.b;
+            for (j, d) in gadget.decompose(*a).into_iter().enumerate() {
+                buff0[i + j * params.m] = d as f64;
             }
-            buff0[i + params.m * (params.kap - 1)] = amod.as_u64() as f64;
         }
 
         // Multiply P^-1 = -1/(b^n/m + 1) (X^(n-m) + b*X^(n-2m) + b^2 X^(n-3m) + ... + b^(n/m-1))
         let mut pinv = -1.0 / (params.p.as_u128() as f64);
         for i in 1..=params.kap {
-            self.monomial_mul_and_add_assign(&buff0, pinv, params.n - i * params.m, &mut buff1);
+            simd::monomial_mul_and_add_assign(&buff0, pinv, params.n - i * params.m, &mut buff1);
             pinv *= bf64;
         }
 
         // Sample a* from coset P^-1 * a.
         for i in 0..params.n {
-            buff1[i] = self.sampler.sample_coset(buff1[i], sigma);
+            buff1[i] = sample(buff1[i]);
         }
 
         // Compute (X^m - b) * a*.
-        for i in 0..params.n - params.m {
-            buff0[i + params.m] = buff1[i] - bf64 * buff1[i + params.m];
-        }
-        for i in params.n - params.m..params.n {
-            buff0[i + params.m - params.n] = -buff1[i] - bf64 * buff1[i + params.m - params.n];
-        }
+        simd::fold_convolution_assign(&buff1, bf64, params.m, &mut buff0);
 
         // Finally, put result into pOut.
         for i in 0..buff0.len() {
@@ -131,6 +155,7 @@ impl<'a> Encoder<'a> {
     }
 
     /// Encodes a chunk of vectors of U256 into a chunk of polynomials, with gaussian noise.
+    #[cfg(not(feature = "rayon"))]
     pub fn encode_randomized_chunk_assign(&mut self, v: &[U256], sigma: f64, pout: &mut [Poly]) {
         if v.len() != pout.len() * self.params.m {
             panic!("invalid length");
@@ -140,6 +165,47 @@ impl<'a> Encoder<'a> {
         }
     }
 
+    /// Encodes a chunk of vectors of U256 into a chunk of polynomials, with
+    /// gaussian noise. For `SamplerKind::Cdt`, the table is built once up
+    /// front and shared read-only across every task, so the batch gets the
+    /// amortization `CdtSampler` promises instead of rebuilding the table per
+    /// polynomial. `Karney` has no shared state to amortize, so each task
+    /// still gets its own fresh sampler.
+    #[cfg(feature = "rayon")]
+    pub fn encode_randomized_chunk_assign(&mut self, v: &[U256], sigma: f64, pout: &mut [Poly]) {
+        if v.len() != pout.len() * self.params.m {
+            panic!("invalid length");
+        }
+        let params = self.params;
+        let gadget = &self.gadget;
+        let m = params.m;
+        match &self.sampler {
+            SamplerKind::Cdt(cdt) => {
+                pout.par_iter_mut().enumerate().for_each(|(i, p)| {
+                    Self::encode_randomized_core(
+                        params,
+                        gadget,
+                        &v[i * m..(i + 1) * m],
+                        p,
+                        |c| cdt.sample_coset(c),
+                    );
+                });
+            }
+            SamplerKind::Karney(_) => {
+                pout.par_iter_mut().enumerate().for_each(|(i, p)| {
+                    let mut karney = KarneySampler::new();
+                    Self::encode_randomized_core(
+                        params,
+                        gadget,
+                        &v[i * m..(i + 1) * m],
+                        p,
+                        |c| karney.sample_coset(c, sigma),
+                    );
+                });
+            }
+        }
+    }
+
     /// Decodes a polynomial into a vector of U256.
     /// Output is always length m.
     pub fn decode(&self, p: &Poly) -> Vec<U256> {
@@ -154,21 +220,18 @@ impl<'a> Encoder<'a> {
         let params = &self.params;
 
         let p_balanced = self.params.ringq.to_balanced(p);
-        let mut tmp = Integer::from(0);
+        let mut digits = vec![0i64; params.kap];
         for i in 0..params.m {
-            vout[i] = U256::from(0);
-            tmp.assign(Integer::ZERO);
-            for j in (0..params.kap).rev() {
-                tmp *= params.b;
-                tmp += Integer::from(p_balanced[0][i + j * params.m]);
+            for j in 0..params.kap {
+                digits[j] = p_balanced[0][i + j * params.m];
             }
-            tmp.rem_euc_assign(&params.p.as_u128());
-            vout[i] = U256::from(tmp.to_u128().unwrap());
+            vout[i] = self.gadget.recompose(&digits);
         }
     }
 
     /// Decodes a chunk of polynomials.
     /// vout must be of length m * p.len().
+    #[cfg(not(feature = "rayon"))]
     pub fn decode_chunk_assign(&self, p: &[Poly], vout: &mut [U256]) {
         if vout.len() != p.len() * self.params.m {
             panic!("invalid length");
@@ -177,11 +240,25 @@ impl<'a> Encoder<'a> {
             self.decode_assign(p, &mut vout[i * self.params.m..(i + 1) * self.params.m]);
         }
     }
+
+    /// Decodes a chunk of polynomials.
+    /// vout must be of length m * p.len().
+    #[cfg(feature = "rayon")]
+    pub fn decode_chunk_assign(&self, p: &[Poly], vout: &mut [U256]) {
+        if vout.len() != p.len() * self.params.m {
+            panic!("invalid length");
+        }
+        let m = self.params.m;
+        p.par_iter()
+            .zip(vout.par_chunks_mut(m))
+            .for_each(|(p, vout_chunk)| self.decode_assign(p, vout_chunk));
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::csprng::*;
+    use crate::sampler::{CdtSampler, SamplerKind};
     use crate::*;
     use primitive_types::U256;
 
@@ -204,4 +281,21 @@ mod tests {
         let mout = ecd.decode(&mr);
         assert_eq!(msg, mout);
     }
+
+    #[test]
+    pub fn test_encoder_cdt_sampler() {
+        let params = Parameters::default();
+        let mut ecd =
+            Encoder::new_with_sampler(&params, SamplerKind::Cdt(CdtSampler::new(params.s1)));
+
+        let mut us = UniformSampler::new();
+
+        let mut msg = vec![U256::from(0); params.m];
+        for j in 0..params.m {
+            msg[j] = us.sample_u256() % params.p;
+        }
+        let mr = ecd.encode_randomized(&msg, params.s1);
+        let mout = ecd.decode(&mr);
+        assert_eq!(msg, mout);
+    }
 }
@@ -0,0 +1,85 @@
+//! Base-`b` gadget decomposition/recomposition, i.e. `G^-1` and `G` for the
+//! gadget vector `G = [1, b, b^2, ..., b^(kap-1)]`. `Encoder::encode_assign`
+//! and `Encoder::decode_assign` are built on this; it's also the primitive
+//! higher-level protocols need when they decompose coefficient vectors
+//! against the same gadget.
+
+use primitive_types::U256;
+
+pub struct Gadget {
+    pub b: U256,
+    pub kap: usize,
+    pub p: U256,
+}
+
+impl Gadget {
+    /// Creates a new gadget for base `b`, `kap` digits, modulus `p`.
+    pub fn new(b: U256, kap: usize, p: U256) -> Gadget {
+        Gadget { b, kap, p }
+    }
+
+    /// Decomposes `x mod p` into `kap` base-`b` digits, least-significant first.
+    pub fn decompose(&self, x: U256) -> Vec<u64> {
+        let mut amod = x % self.p;
+        let mut digits = vec![0u64; self.kap];
+        for d in digits.iter_mut().take(self.kap - 1) {
+            *d = (amod % self.b).as_u64();
+            amod /= self.b;
+        }
+        digits[self.kap - 1] = amod.as_u64();
+        digits
+    }
+
+    /// Recomposes `kap` base-`b` digits (least-significant first) into a
+    /// value mod `p` via Horner's method. Digits may be negative, so that
+    /// balanced/centered representations (as produced by `RingQ::to_balanced`)
+    /// can be fed straight back in.
+    pub fn recompose(&self, digits: &[i64]) -> U256 {
+        let mut tmp = U256::from(0);
+        for &d in digits.iter().rev() {
+            tmp = (tmp * self.b) % self.p;
+            if d >= 0 {
+                tmp = (tmp + U256::from(d as u64)) % self.p;
+            } else {
+                let dm = U256::from((-d) as u64) % self.p;
+                tmp = if tmp >= dm {
+                    tmp - dm
+                } else {
+                    tmp + self.p - dm
+                };
+            }
+        }
+        tmp
+    }
+
+    /// Returns the gadget vector `[1, b, b^2, ..., b^(kap-1)] mod p`.
+    pub fn gadget_vector(&self) -> Vec<U256> {
+        let mut v = vec![U256::from(0); self.kap];
+        v[0] = U256::from(1) % self.p;
+        for i in 1..self.kap {
+            v[i] = (v[i - 1] * self.b) % self.p;
+        }
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csprng::*;
+    use crate::*;
+
+    #[test]
+    pub fn test_gadget_round_trip() {
+        let params = Parameters::default();
+        let gadget = Gadget::new(params.b, params.kap, params.p);
+
+        let mut us = UniformSampler::new();
+        for _ in 0..100 {
+            let x = us.sample_u256() % params.p;
+            let digits = gadget.decompose(x);
+            let digits_i64: Vec<i64> = digits.iter().map(|&d| d as i64).collect();
+            assert_eq!(gadget.recompose(&digits_i64), x);
+        }
+    }
+}
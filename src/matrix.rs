@@ -1,24 +1,83 @@
-use primitive_types::U256;
+use primitive_types::{U256, U512};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
 pub struct SparseMatrix {
     pub n: usize,
     pub q: U256,
 
-    // CSR Format
-    pub row_ptr: Vec<usize>,
-    pub col_idx: Vec<usize>,
-    pub values: Vec<U256>,
+    // Barrett reduction precomputation, keyed to `q`.
+    k: u32,
+    mu: U512,
+
+    // CSR Format. Private so the only way to replace them is `set_csr`,
+    // which also invalidates `csc` -- mutating them directly would leave
+    // any already-built `csc` silently stale.
+    row_ptr: Vec<usize>,
+    col_idx: Vec<usize>,
+    values: Vec<U256>,
+
+    // Column-compressed companion, i.e. the CSR of the transpose, built
+    // on demand by `build_csc` and reused by `transpose_mul_vec*`.
+    csc: Option<Box<SparseMatrix>>,
+}
+
+/// Largest modulus bit-length `reduce`'s `U512` arithmetic can handle:
+/// `x < 2^(2k)` and `mu < 2^(k+1)`, so `x * mu` needs `3k + 1` bits, which
+/// must fit in `U512`.
+const MAX_MODULUS_BITS: u32 = 170;
+
+/// Computes the Barrett parameters `(k, mu)` for modulus `q`, where
+/// `k = bit_length(q)` and `mu = floor(2^(2k) / q)`.
+///
+/// Panics if `q` is wider than `MAX_MODULUS_BITS` bits: `reduce`'s `x * mu`
+/// would overflow `U512` (a hard panic in `primitive_types`, release builds
+/// included) for any larger modulus, so this is checked eagerly at
+/// construction instead of failing deep inside a hot-path multiply.
+fn barrett_params(q: U256) -> (u32, U512) {
+    let k = q.bits() as u32;
+    assert!(
+        k <= MAX_MODULUS_BITS,
+        "SparseMatrix only supports moduli up to {} bits (got a {}-bit q); \
+         a wider q would overflow the U512 Barrett reduction",
+        MAX_MODULUS_BITS,
+        k
+    );
+    let two_k = 2 * k;
+    let q512 = U512::from(q);
+
+    // `2^(2k) - 1` instead of `2^(2k)` avoids an out-of-range shift when
+    // `2k == 512`, and gives the same floor division since `q` is odd.
+    let numerator = if two_k >= 512 {
+        U512::MAX
+    } else {
+        (U512::one() << two_k) - U512::one()
+    };
+
+    (k, numerator / q512)
+}
+
+/// Narrows `x` (known to be `< 2^256`) back down to `U256`.
+fn u512_to_u256(x: U512) -> U256 {
+    debug_assert!(x.0[4..8].iter().all(|&limb| limb == 0));
+    let mut limbs = [0u64; 4];
+    limbs.copy_from_slice(&x.0[0..4]);
+    U256(limbs)
 }
 
 impl SparseMatrix {
     /// Create a new SparseMatrix with n rows and q modulus.
     pub fn new(n: usize, q: U256) -> SparseMatrix {
+        let (k, mu) = barrett_params(q);
         SparseMatrix {
             n,
             q: q,
+            k,
+            mu,
             row_ptr: vec![0; n + 1],
             col_idx: vec![],
             values: vec![],
+            csc: None,
         }
     }
 
@@ -35,13 +94,95 @@ impl SparseMatrix {
         }
         row_ptr[n] = n;
 
+        let (k, mu) = barrett_params(q);
         SparseMatrix {
             n: n,
             q: q,
+            k,
+            mu,
             row_ptr: row_ptr,
             col_idx: col_idx,
             values: values,
+            csc: None,
+        }
+    }
+
+    /// Replaces the CSR data wholesale (`row_ptr.len() == n + 1`,
+    /// `col_idx.len() == values.len() == nnz`). Invalidates any
+    /// already-built `csc` companion, since it was built from the old data
+    /// and `build_csc` must be called again to pick up the change.
+    pub fn set_csr(&mut self, row_ptr: Vec<usize>, col_idx: Vec<usize>, values: Vec<U256>) {
+        self.row_ptr = row_ptr;
+        self.col_idx = col_idx;
+        self.values = values;
+        self.csc = None;
+    }
+
+    /// Builds (if not already built) a column-compressed companion matrix —
+    /// equivalently the CSR of the transpose — via a counting-sort transpose
+    /// of `row_ptr`/`col_idx`/`values`. Once built, `transpose_mul_vec*`
+    /// prefer it over scattering into `vout[col_idx[j]]`.
+    pub fn build_csc(&mut self) {
+        if self.csc.is_some() {
+            return;
+        }
+
+        let nnz = self.values.len();
+        let mut row_ptr_t = vec![0usize; self.n + 1];
+        for &c in &self.col_idx {
+            row_ptr_t[c + 1] += 1;
         }
+        for i in 0..self.n {
+            row_ptr_t[i + 1] += row_ptr_t[i];
+        }
+
+        let mut col_idx_t = vec![0usize; nnz];
+        let mut values_t = vec![U256::from(0); nnz];
+        let mut next = row_ptr_t.clone();
+
+        for i in 0..self.n {
+            for j in self.row_ptr[i]..self.row_ptr[i + 1] {
+                let c = self.col_idx[j];
+                let dest = next[c];
+                col_idx_t[dest] = i;
+                values_t[dest] = self.values[j];
+                next[c] += 1;
+            }
+        }
+
+        self.csc = Some(Box::new(SparseMatrix {
+            n: self.n,
+            q: self.q,
+            k: self.k,
+            mu: self.mu,
+            row_ptr: row_ptr_t,
+            col_idx: col_idx_t,
+            values: values_t,
+            csc: None,
+        }));
+    }
+
+    /// Reduces `x < q^2` modulo `q` via the precomputed Barrett reducer, in
+    /// fixed-width `U512` arithmetic only (no heap-allocating bignum, no
+    /// per-entry division). `barrett_params` already rejected any `q` wider
+    /// than `MAX_MODULUS_BITS` at construction time, so `x * mu` is
+    /// guaranteed to fit in `U512` here (`U256` is sized for product
+    /// headroom, not because `q` itself needs all 256 bits).
+    #[inline]
+    fn reduce(&self, x: U512) -> U256 {
+        debug_assert!(3 * self.k as u64 + 1 <= 512);
+
+        let q512 = U512::from(self.q);
+        let t = (x * self.mu) >> (2 * self.k);
+        let mut r = x - t * q512;
+        if r >= q512 {
+            r -= q512;
+        }
+        if r >= q512 {
+            r -= q512;
+        }
+
+        u512_to_u256(r)
     }
 
     /// Transforms a sparse matrix to dense matrix.
@@ -65,30 +206,76 @@ impl SparseMatrix {
     }
 
     /// Multiplies v and writes it to vout.
+    #[cfg(not(feature = "rayon"))]
     pub fn mul_vec_assign(&self, v: &[U256], vout: &mut [U256]) {
         for i in 0..self.n {
             vout[i] = U256::from(0);
             for j in self.row_ptr[i]..self.row_ptr[i + 1] {
-                vout[i] = (vout[i] + self.values[j] * v[self.col_idx[j]]) % self.q;
+                let prod = self.reduce(U512::from(self.values[j]) * U512::from(v[self.col_idx[j]]));
+                vout[i] = vout[i] + prod;
+                if vout[i] >= self.q {
+                    vout[i] = vout[i] - self.q;
+                }
             }
         }
     }
 
+    /// Multiplies v and writes it to vout. Rows are independent, so each is
+    /// computed on its own task.
+    #[cfg(feature = "rayon")]
+    pub fn mul_vec_assign(&self, v: &[U256], vout: &mut [U256]) {
+        vout.par_iter_mut().enumerate().for_each(|(i, out)| {
+            *out = U256::from(0);
+            for j in self.row_ptr[i]..self.row_ptr[i + 1] {
+                let prod = self.reduce(U512::from(self.values[j]) * U512::from(v[self.col_idx[j]]));
+                *out = *out + prod;
+                if *out >= self.q {
+                    *out = *out - self.q;
+                }
+            }
+        });
+    }
+
     /// Multiplies v and adds it to vout.
+    #[cfg(not(feature = "rayon"))]
     pub fn mul_vec_add_assign(&self, v: &[U256], vout: &mut [U256]) {
         for i in 0..self.n {
             for j in self.row_ptr[i]..self.row_ptr[i + 1] {
-                vout[i] = (vout[i] + self.values[j] * v[self.col_idx[j]]) % self.q;
+                let prod = self.reduce(U512::from(self.values[j]) * U512::from(v[self.col_idx[j]]));
+                vout[i] = vout[i] + prod;
+                if vout[i] >= self.q {
+                    vout[i] = vout[i] - self.q;
+                }
             }
         }
     }
 
+    /// Multiplies v and adds it to vout. Rows are independent, so each is
+    /// computed on its own task.
+    #[cfg(feature = "rayon")]
+    pub fn mul_vec_add_assign(&self, v: &[U256], vout: &mut [U256]) {
+        vout.par_iter_mut().enumerate().for_each(|(i, out)| {
+            for j in self.row_ptr[i]..self.row_ptr[i + 1] {
+                let prod = self.reduce(U512::from(self.values[j]) * U512::from(v[self.col_idx[j]]));
+                *out = *out + prod;
+                if *out >= self.q {
+                    *out = *out - self.q;
+                }
+            }
+        });
+    }
+
     /// Multiplies v and subtracts it from vout.
+    #[cfg(not(feature = "rayon"))]
     pub fn mul_vec_sub_assign(&self, v: &[U256], vout: &mut [U256]) {
         for i in 0..self.n {
             let mut tmp = U256::from(0);
             for j in self.row_ptr[i]..self.row_ptr[i + 1] {
-                tmp = (tmp + self.values[j] * v[self.col_idx[j]]) % self.q;
+                let prod = self.reduce(U512::from(self.values[j]) * U512::from(v[self.col_idx[j]]));
+                tmp = tmp + prod;
+                if tmp >= self.q {
+                    tmp = tmp - self.q;
+                }
             }
             if vout[i] >= tmp {
                 vout[i] = vout[i] - tmp
@@ -98,6 +285,27 @@ impl SparseMatrix {
         }
     }
 
+    /// Multiplies v and subtracts it from vout. Rows are independent, so
+    /// each is computed on its own task.
+    #[cfg(feature = "rayon")]
+    pub fn mul_vec_sub_assign(&self, v: &[U256], vout: &mut [U256]) {
+        vout.par_iter_mut().enumerate().for_each(|(i, out)| {
+            let mut tmp = U256::from(0);
+            for j in self.row_ptr[i]..self.row_ptr[i + 1] {
+                let prod = self.reduce(U512::from(self.values[j]) * U512::from(v[self.col_idx[j]]));
+                tmp = tmp + prod;
+                if tmp >= self.q {
+                    tmp = tmp - self.q;
+                }
+            }
+            if *out >= tmp {
+                *out = *out - tmp
+            } else {
+                *out = *out + self.q - tmp
+            }
+        });
+    }
+
     /// Transposes and multiplies v and returns the result.
     pub fn transpose_mul_vec(&self, v: &[U256]) -> Vec<U256> {
         let mut vout = vec![U256::from(0); self.n];
@@ -106,38 +314,302 @@ impl SparseMatrix {
     }
 
     /// Transposes and multiplies v and writes it to vout.
+    #[cfg(not(feature = "rayon"))]
     pub fn transpose_mul_vec_assign(&self, v: &[U256], vout: &mut [U256]) {
+        if let Some(csc) = &self.csc {
+            csc.mul_vec_assign(v, vout);
+            return;
+        }
+
         for i in 0..self.n {
             vout[i] = U256::from(0);
         }
 
         for i in 0..self.n {
             for j in self.row_ptr[i]..self.row_ptr[i + 1] {
-                vout[self.col_idx[j]] = (vout[self.col_idx[j]] + self.values[j] * v[i]) % self.q;
+                let prod = self.reduce(U512::from(self.values[j]) * U512::from(v[i]));
+                let idx = self.col_idx[j];
+                vout[idx] = vout[idx] + prod;
+                if vout[idx] >= self.q {
+                    vout[idx] = vout[idx] - self.q;
+                }
             }
         }
     }
 
     /// Transposes and multiplies v and adds it to vout.
+    #[cfg(not(feature = "rayon"))]
     pub fn transpose_mul_vec_add_assign(&self, v: &[U256], vout: &mut [U256]) {
+        if let Some(csc) = &self.csc {
+            csc.mul_vec_add_assign(v, vout);
+            return;
+        }
+
         for i in 0..self.n {
             for j in self.row_ptr[i]..self.row_ptr[i + 1] {
-                vout[self.col_idx[j]] = (vout[self.col_idx[j]] + self.values[j] * v[i]) % self.q;
+                let prod = self.reduce(U512::from(self.values[j]) * U512::from(v[i]));
+                let idx = self.col_idx[j];
+                vout[idx] = vout[idx] + prod;
+                if vout[idx] >= self.q {
+                    vout[idx] = vout[idx] - self.q;
+                }
             }
         }
     }
 
     /// Transposes and multiplies v and subtracts it from vout.
+    #[cfg(not(feature = "rayon"))]
     pub fn transpose_mul_vec_sub_assign(&self, v: &[U256], vout: &mut [U256]) {
+        if let Some(csc) = &self.csc {
+            csc.mul_vec_sub_assign(v, vout);
+            return;
+        }
+
         for i in 0..self.n {
             for j in self.row_ptr[i]..self.row_ptr[i + 1] {
-                let tmp = (self.values[j] * v[i]) % self.q;
-                if vout[self.col_idx[j]] >= tmp {
-                    vout[self.col_idx[j]] = vout[self.col_idx[j]] - tmp
+                let tmp = self.reduce(U512::from(self.values[j]) * U512::from(v[i]));
+                let idx = self.col_idx[j];
+                if vout[idx] >= tmp {
+                    vout[idx] = vout[idx] - tmp
                 } else {
-                    vout[self.col_idx[j]] = vout[self.col_idx[j]] + self.q - tmp;
+                    vout[idx] = vout[idx] + self.q - tmp;
                 }
             }
         }
     }
+
+    /// Accumulates `A^T v` into per-thread buffers and reduces them into a
+    /// single `n`-length vector. The scatter `vout[col_idx[j]] += ...` touches
+    /// overlapping columns across rows, so unlike the `mul_vec*` family this
+    /// can't split cleanly across tasks without a merge step.
+    #[cfg(feature = "rayon")]
+    fn transpose_mul_vec_reduce(&self, v: &[U256]) -> Vec<U256> {
+        (0..self.n)
+            .into_par_iter()
+            .fold(
+                || vec![U256::from(0); self.n],
+                |mut acc, i| {
+                    for j in self.row_ptr[i]..self.row_ptr[i + 1] {
+                        let prod = self.reduce(U512::from(self.values[j]) * U512::from(v[i]));
+                        let idx = self.col_idx[j];
+                        acc[idx] = acc[idx] + prod;
+                        if acc[idx] >= self.q {
+                            acc[idx] = acc[idx] - self.q;
+                        }
+                    }
+                    acc
+                },
+            )
+            .reduce(
+                || vec![U256::from(0); self.n],
+                |mut a, b| {
+                    for i in 0..self.n {
+                        a[i] = a[i] + b[i];
+                        if a[i] >= self.q {
+                            a[i] = a[i] - self.q;
+                        }
+                    }
+                    a
+                },
+            )
+    }
+
+    /// Transposes and multiplies v and writes it to vout.
+    #[cfg(feature = "rayon")]
+    pub fn transpose_mul_vec_assign(&self, v: &[U256], vout: &mut [U256]) {
+        if let Some(csc) = &self.csc {
+            csc.mul_vec_assign(v, vout);
+            return;
+        }
+        vout.copy_from_slice(&self.transpose_mul_vec_reduce(v));
+    }
+
+    /// Transposes and multiplies v and adds it to vout.
+    #[cfg(feature = "rayon")]
+    pub fn transpose_mul_vec_add_assign(&self, v: &[U256], vout: &mut [U256]) {
+        if let Some(csc) = &self.csc {
+            csc.mul_vec_add_assign(v, vout);
+            return;
+        }
+
+        for (out, acc) in vout.iter_mut().zip(self.transpose_mul_vec_reduce(v)) {
+            *out = *out + acc;
+            if *out >= self.q {
+                *out = *out - self.q;
+            }
+        }
+    }
+
+    /// Transposes and multiplies v and subtracts it from vout.
+    #[cfg(feature = "rayon")]
+    pub fn transpose_mul_vec_sub_assign(&self, v: &[U256], vout: &mut [U256]) {
+        if let Some(csc) = &self.csc {
+            csc.mul_vec_sub_assign(v, vout);
+            return;
+        }
+
+        for (out, acc) in vout.iter_mut().zip(self.transpose_mul_vec_reduce(v)) {
+            if *out >= acc {
+                *out = *out - acc;
+            } else {
+                *out = *out + self.q - acc;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3x3 matrix:
+    // [1 2 0]
+    // [0 3 4]
+    // [5 0 6]
+    fn sample_matrix(q: U256) -> SparseMatrix {
+        let mut m = SparseMatrix::new(3, q);
+        m.set_csr(
+            vec![0, 2, 4, 6],
+            vec![0, 1, 1, 2, 0, 2],
+            vec![
+                U256::from(1),
+                U256::from(2),
+                U256::from(3),
+                U256::from(4),
+                U256::from(5),
+                U256::from(6),
+            ],
+        );
+        m
+    }
+
+    fn naive_mul_vec(dense: &[Vec<U256>], v: &[U256], q: U256) -> Vec<U256> {
+        let n = dense.len();
+        let mut out = vec![U256::from(0); n];
+        for i in 0..n {
+            let mut acc = U256::from(0);
+            for j in 0..n {
+                acc = (acc + dense[i][j] * v[j]) % q;
+            }
+            out[i] = acc;
+        }
+        out
+    }
+
+    fn transpose_dense(dense: &[Vec<U256>]) -> Vec<Vec<U256>> {
+        let n = dense.len();
+        let mut out = vec![vec![U256::from(0); n]; n];
+        for i in 0..n {
+            for j in 0..n {
+                out[i][j] = dense[j][i];
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_mul_vec_matches_dense() {
+        let q = U256::from(101u64);
+        let m = sample_matrix(q);
+        let dense = m.to_dense();
+        let v = vec![U256::from(7), U256::from(11), U256::from(13)];
+
+        assert_eq!(m.mul_vec(&v), naive_mul_vec(&dense, &v, q));
+    }
+
+    #[test]
+    fn test_transpose_mul_vec_matches_dense() {
+        let q = U256::from(101u64);
+        let m = sample_matrix(q);
+        let dense_t = transpose_dense(&m.to_dense());
+        let v = vec![U256::from(7), U256::from(11), U256::from(13)];
+
+        assert_eq!(m.transpose_mul_vec(&v), naive_mul_vec(&dense_t, &v, q));
+    }
+
+    #[test]
+    fn test_csc_matches_scatter_fallback() {
+        let q = U256::from(101u64);
+        let mut m = sample_matrix(q);
+        let v = vec![U256::from(7), U256::from(11), U256::from(13)];
+
+        let scatter = m.transpose_mul_vec(&v);
+        m.build_csc();
+        let gathered = m.transpose_mul_vec(&v);
+        assert_eq!(scatter, gathered);
+    }
+
+    #[test]
+    fn test_set_csr_invalidates_csc() {
+        let q = U256::from(101u64);
+        let mut m = sample_matrix(q);
+        let v = vec![U256::from(7), U256::from(11), U256::from(13)];
+        m.build_csc();
+
+        // Replace the CSR data wholesale; the cached CSC companion was
+        // built from the old data and must not be reused silently.
+        m.set_csr(
+            vec![0, 1, 2, 3],
+            vec![0, 1, 2],
+            vec![U256::from(9), U256::from(8), U256::from(7)],
+        );
+
+        let dense_t = transpose_dense(&m.to_dense());
+        assert_eq!(m.transpose_mul_vec(&v), naive_mul_vec(&dense_t, &v, q));
+    }
+
+    #[test]
+    fn test_reduce_matches_naive_mod() {
+        let q = U256::from(10007u64);
+        let m = SparseMatrix::new(1, q);
+
+        for a in 0..200u64 {
+            for b in 0..200u64 {
+                let x = U512::from(U256::from(a)) * U512::from(U256::from(b));
+                let expect = (U256::from(a) * U256::from(b)) % q;
+                assert_eq!(m.reduce(x), expect);
+            }
+        }
+    }
+
+    // Wall-clock comparisons are flaky on loaded/throttled CI runners, so
+    // this isn't run as part of `cargo test` -- it's a manual check
+    // (`cargo test -- --ignored test_reduce_is_not_much_slower_than_naive_mod`)
+    // that Barrett reduction stays in the same ballpark as naive `%`,
+    // rather than a correctness assertion. A proper benchmark belongs in a
+    // `benches/` criterion harness.
+    #[test]
+    #[ignore]
+    fn test_reduce_is_not_much_slower_than_naive_mod() {
+        let q = (U256::from(1u64) << 127) - U256::from(1u64);
+        let m = SparseMatrix::new(1, q);
+
+        let iters = 20_000u64;
+        let xs: Vec<U512> = (0..iters)
+            .map(|i| {
+                let a = (U256::from(i) << 64) + U256::from(i * 2654435761 + 1);
+                let b = (U256::from(i * 40503) << 64) + U256::from(i + 1);
+                U512::from(a % q) * U512::from(b % q)
+            })
+            .collect();
+
+        let start = std::time::Instant::now();
+        for &x in &xs {
+            std::hint::black_box(m.reduce(x));
+        }
+        let barrett_elapsed = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for &x in &xs {
+            std::hint::black_box(u512_to_u256(x % U512::from(q)));
+        }
+        let naive_elapsed = start.elapsed();
+
+        assert!(
+            barrett_elapsed <= naive_elapsed * 3 + std::time::Duration::from_millis(1),
+            "Barrett reduce ({:?}) should not be dramatically slower than naive `%` ({:?})",
+            barrett_elapsed,
+            naive_elapsed
+        );
+    }
 }
@@ -0,0 +1,97 @@
+//! Discrete-Gaussian sampler selection for `Encoder::encode_randomized_assign`.
+//!
+//! `KarneySampler` (see `csprng`) is a rejection sampler: both its timing and
+//! its iteration count depend on the value being sampled, which is
+//! undesirable in side-channel-sensitive deployments and wasteful when
+//! `sigma` is fixed across a whole batch. `CdtSampler` trades a one-time
+//! table build for constant-time, fixed-iteration sampling at that fixed
+//! `sigma`.
+
+use super::csprng::KarneySampler;
+use rand::Rng;
+
+/// Selects which discrete-Gaussian sampler `Encoder` uses.
+pub enum SamplerKind {
+    Karney(KarneySampler),
+    Cdt(CdtSampler),
+}
+
+impl SamplerKind {
+    /// Samples from the coset `c + Z`. `sigma` is only consulted by the
+    /// `Karney` variant; `Cdt` was already built for a fixed `sigma` and
+    /// ignores it here.
+    pub fn sample_coset(&mut self, c: f64, sigma: f64) -> f64 {
+        match self {
+            SamplerKind::Karney(s) => s.sample_coset(c, sigma),
+            SamplerKind::Cdt(s) => s.sample_coset(c),
+        }
+    }
+}
+
+/// A cumulative-distribution-table sampler for a fixed `sigma`. The table is
+/// built once, at construction, and amortized across every coefficient of a
+/// batched `encode_randomized_chunk_assign` call.
+pub struct CdtSampler {
+    // Integer offsets from the (rounded) center, covering +-tau*sigma.
+    offsets: Vec<i64>,
+    // Cumulative probabilities as fixed-point thresholds in [0, 1 << SCALE_BITS].
+    thresholds: Vec<u128>,
+}
+
+impl CdtSampler {
+    // tau ~= 12 gives a tail probability far below 2^-100.
+    const TAU: f64 = 12.0;
+    const SCALE_BITS: u32 = 120;
+
+    /// Builds the cumulative distribution table for discrete Gaussian noise
+    /// of standard deviation `sigma`, truncated to `+-TAU * sigma`.
+    pub fn new(sigma: f64) -> CdtSampler {
+        let bound = (Self::TAU * sigma).ceil() as i64;
+        let offsets: Vec<i64> = (-bound..=bound).collect();
+
+        let weights: Vec<f64> = offsets
+            .iter()
+            .map(|&x| (-((x * x) as f64) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let total: f64 = weights.iter().sum();
+
+        let scale = (1u128 << Self::SCALE_BITS) as f64;
+        let mut thresholds = Vec::with_capacity(offsets.len());
+        let mut acc = 0.0;
+        for w in &weights {
+            acc += w / total;
+            thresholds.push((acc * scale).round() as u128);
+        }
+        // Force the last threshold to cover the full range, in case of
+        // rounding error in the accumulation above.
+        *thresholds.last_mut().unwrap() = 1u128 << Self::SCALE_BITS;
+
+        CdtSampler { offsets, thresholds }
+    }
+
+    /// Samples from the coset `c + Z` in data-independent time: every table
+    /// entry is folded into the result via an arithmetic indicator, rather
+    /// than returning as soon as a threshold is cleared.
+    ///
+    /// Every element of `c + Z` is `c + k` for an integer `k`, and the
+    /// Gaussian weight of `c + k` relative to center `c` depends only on `k`
+    /// (it's `exp(-k^2 / (2*sigma^2))`), independent of `c`'s fractional
+    /// part. That's exactly the table built by `new`, keyed on integer
+    /// offsets from 0 -- so the offset it draws applies on top of `c`
+    /// itself. Previously this rounded `c` first, which silently collapsed
+    /// every sample to the same fractional part and changed the sampled
+    /// distribution.
+    pub fn sample_coset(&self, c: f64) -> f64 {
+        let u: u128 = rand::thread_rng().gen_range(0..(1u128 << Self::SCALE_BITS));
+
+        let mut offset_acc: i64 = 0;
+        let mut prev_threshold: u128 = 0;
+        for (i, &threshold) in self.thresholds.iter().enumerate() {
+            let indicator = (u >= prev_threshold && u < threshold) as i64;
+            offset_acc += indicator * self.offsets[i];
+            prev_threshold = threshold;
+        }
+
+        c + offset_acc as f64
+    }
+}
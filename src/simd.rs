@@ -0,0 +1,167 @@
+//! AVX2 kernels for the randomized-encode float pipeline, with scalar
+//! fallbacks for targets built without `target-feature=+avx2`.
+
+use std::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use std::ops::{Deref, DerefMut};
+
+/// An owning `[f64]` buffer allocated with a 64-byte-aligned `Layout`.
+///
+/// `Vec<f64>` can't be used for this: its `Drop`/realloc paths deallocate
+/// using `Layout::array::<f64>()`, whose alignment is 8, not the 64-byte
+/// layout the buffer was actually allocated with. Handing a 64-byte-aligned
+/// pointer to `Vec::from_raw_parts` would violate its safety contract and
+/// corrupt the heap on allocators that size-class by alignment. This type
+/// deallocates with the exact `Layout` it was allocated with instead.
+///
+/// Note that the base pointer being 64-byte aligned doesn't, by itself, make
+/// every SIMD access in this file an aligned one: the AVX2 kernels below load
+/// and store via `_mm256_loadu_pd`/`_mm256_storeu_pd` at offsets (`i+d`,
+/// `i+m`) that aren't generally 4-`f64`-aligned, so they take the unaligned
+/// path regardless. The alignment only guarantees the buffer never straddles
+/// more cache lines than necessary.
+pub(crate) struct AlignedF64Buf {
+    ptr: *mut f64,
+    len: usize,
+    layout: Layout,
+}
+
+impl AlignedF64Buf {
+    fn new(n: usize) -> AlignedF64Buf {
+        let layout = Layout::from_size_align(n * std::mem::size_of::<f64>(), 64).unwrap();
+        let ptr = unsafe { alloc_zeroed(layout) as *mut f64 };
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        AlignedF64Buf { ptr, len: n, layout }
+    }
+}
+
+impl Deref for AlignedF64Buf {
+    type Target = [f64];
+    fn deref(&self) -> &[f64] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for AlignedF64Buf {
+    fn deref_mut(&mut self) -> &mut [f64] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for AlignedF64Buf {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr as *mut u8, self.layout) }
+    }
+}
+
+// Safety: `AlignedF64Buf` owns its allocation exclusively, like `Vec<f64>`.
+unsafe impl Send for AlignedF64Buf {}
+unsafe impl Sync for AlignedF64Buf {}
+
+/// Allocates a zeroed buffer of length `n` whose base pointer is 64-byte
+/// (cache-line) aligned. The AVX2 kernels below still use unaligned
+/// loads/stores at arbitrary offsets, which can cross cache-line boundaries.
+pub(crate) fn aligned_f64_vec(n: usize) -> AlignedF64Buf {
+    AlignedF64Buf::new(n)
+}
+
+/// Computes `pout[i + d] += c * p[i]` for `i < n - d`, wrapping around as
+/// `pout[i + d - n] -= c * p[i]` for `i >= n - d` (i.e. `pout += p * c*X^d`
+/// reduced modulo `X^n + 1`).
+#[cfg(target_feature = "avx2")]
+pub(crate) fn monomial_mul_and_add_assign(p: &[f64], c: f64, d: usize, pout: &mut [f64]) {
+    use std::arch::x86_64::*;
+    let n = p.len();
+    let head = n - d;
+
+    unsafe {
+        let cv = _mm256_set1_pd(c);
+
+        let mut i = 0;
+        while i + 4 <= head {
+            let pv = _mm256_loadu_pd(p.as_ptr().add(i));
+            let ov = _mm256_loadu_pd(pout.as_ptr().add(i + d));
+            _mm256_storeu_pd(pout.as_mut_ptr().add(i + d), _mm256_fmadd_pd(cv, pv, ov));
+            i += 4;
+        }
+        while i < head {
+            pout[i + d] += c * p[i];
+            i += 1;
+        }
+
+        let mut i = head;
+        while i + 4 <= n {
+            let pv = _mm256_loadu_pd(p.as_ptr().add(i));
+            let ov = _mm256_loadu_pd(pout.as_ptr().add(i + d - n));
+            _mm256_storeu_pd(pout.as_mut_ptr().add(i + d - n), _mm256_fnmadd_pd(cv, pv, ov));
+            i += 4;
+        }
+        while i < n {
+            pout[i + d - n] -= c * p[i];
+            i += 1;
+        }
+    }
+}
+
+#[cfg(not(target_feature = "avx2"))]
+pub(crate) fn monomial_mul_and_add_assign(p: &[f64], c: f64, d: usize, pout: &mut [f64]) {
+    let n = p.len();
+    for i in 0..n - d {
+        pout[i + d] += c * p[i];
+    }
+    for i in n - d..n {
+        pout[i + d - n] -= c * p[i];
+    }
+}
+
+/// Computes `buff0[i + m] = buff1[i] - b * buff1[i + m]` for `i < n - m`,
+/// wrapping around as `buff0[i + m - n] = -buff1[i] - b * buff1[i + m - n]`
+/// for `i >= n - m` (i.e. `buff0 = (X^m - b) * buff1` reduced modulo `X^n + 1`).
+#[cfg(target_feature = "avx2")]
+pub(crate) fn fold_convolution_assign(buff1: &[f64], b: f64, m: usize, buff0: &mut [f64]) {
+    use std::arch::x86_64::*;
+    let n = buff1.len();
+    let head = n - m;
+
+    unsafe {
+        let bv = _mm256_set1_pd(b);
+        let signbit = _mm256_set1_pd(-0.0);
+
+        let mut i = 0;
+        while i + 4 <= head {
+            let lo = _mm256_loadu_pd(buff1.as_ptr().add(i));
+            let hi = _mm256_loadu_pd(buff1.as_ptr().add(i + m));
+            _mm256_storeu_pd(buff0.as_mut_ptr().add(i + m), _mm256_fnmadd_pd(bv, hi, lo));
+            i += 4;
+        }
+        while i < head {
+            buff0[i + m] = buff1[i] - b * buff1[i + m];
+            i += 1;
+        }
+
+        let mut i = head;
+        while i + 4 <= n {
+            let lo = _mm256_loadu_pd(buff1.as_ptr().add(i));
+            let hi = _mm256_loadu_pd(buff1.as_ptr().add(i + m - n));
+            let neg_lo = _mm256_xor_pd(lo, signbit);
+            _mm256_storeu_pd(buff0.as_mut_ptr().add(i + m - n), _mm256_fnmadd_pd(bv, hi, neg_lo));
+            i += 4;
+        }
+        while i < n {
+            buff0[i + m - n] = -buff1[i] - b * buff1[i + m - n];
+            i += 1;
+        }
+    }
+}
+
+#[cfg(not(target_feature = "avx2"))]
+pub(crate) fn fold_convolution_assign(buff1: &[f64], b: f64, m: usize, buff0: &mut [f64]) {
+    let n = buff1.len();
+    for i in 0..n - m {
+        buff0[i + m] = buff1[i] - b * buff1[i + m];
+    }
+    for i in n - m..n {
+        buff0[i + m - n] = -buff1[i] - b * buff1[i + m - n];
+    }
+}